@@ -1,7 +1,9 @@
-use super::FileAttribute;
+use super::{Directory, FileAttribute};
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
 use crate::data_types::chars::NUL_16;
 use crate::table::runtime::Time;
-use crate::{CStr16, Char16, Guid, Identify};
+use crate::{CStr16, Char16, Guid, Identify, Status};
 use core::cmp;
 use core::convert::TryInto;
 use core::ffi::c_void;
@@ -25,6 +27,49 @@ pub trait FromUefi {
     unsafe fn from_uefi<'a>(ptr: *mut c_void) -> &'a mut Self;
 }
 
+/// Trait describing the memory alignment requirement of a FileProtocolInfo
+///
+/// The data structures exposed by this module are dynamic-sized types whose
+/// alignment cannot be recovered through `mem::align_of` (it does not support
+/// DSTs). This trait centralizes the one genuinely tricky invariant of this
+/// module — the relationship between the storage pointer, the header, and the
+/// trailing UCS-2 name — in a single audited place, and gives callers a way to
+/// pre-check or realign their buffers before handing them to the constructors.
+pub trait Align {
+    /// Required memory alignment for this type
+    fn alignment() -> usize;
+
+    /// Debug-assert that some storage is correctly aligned for this type
+    fn assert_aligned(storage: &mut [u8]) {
+        if !storage.is_empty() {
+            debug_assert_eq!((storage.as_ptr() as usize) % Self::alignment(), 0);
+        }
+    }
+
+    /// Copy-less realignment of a storage buffer for this type
+    ///
+    /// Return the sub-slice of `storage` that starts at the first correctly
+    /// aligned address, or `None` if the buffer is too small to contain such an
+    /// address. The first few bytes of `storage` may be discarded in the
+    /// process, resulting in a reduction of effective storage capacity.
+    fn align_buf(storage: &mut [u8]) -> Option<&mut [u8]> {
+        let padding = storage.as_ptr().align_offset(Self::alignment());
+        if storage.len() < padding {
+            None
+        } else {
+            let realigned = &mut storage[padding..];
+            Self::assert_aligned(realigned);
+            Some(realigned)
+        }
+    }
+}
+
+impl<Header> Align for NamedFileProtocolInfo<Header> {
+    fn alignment() -> usize {
+        cmp::max(mem::align_of::<Header>(), mem::align_of::<Char16>())
+    }
+}
+
 /// Dynamically sized FileProtocolInfo with a header and an UCS-2 name
 ///
 /// All struct that can currently be queried via Get/SetInfo can be described as
@@ -46,23 +91,8 @@ impl<Header> NamedFileProtocolInfo<Header> {
     /// Correct the alignment of a storage buffer for this type by discarding the first few bytes
     ///
     /// Return an empty slice if the storage is not large enough to perform this operation
-    pub fn realign_storage(mut storage: &mut [u8]) -> &mut [u8] {
-        // Compute the degree of storage misalignment. mem::align_of does not
-        // support dynamically sized types, so we must help it a bit.
-        let storage_address = storage.as_ptr() as usize;
-        let info_alignment = cmp::max(mem::align_of::<Header>(), mem::align_of::<Char16>());
-        let storage_misalignment = storage_address % info_alignment;
-        let realignment_padding = info_alignment - storage_misalignment;
-
-        // Return an empty slice if the storage is too small to be realigned
-        if storage.len() < realignment_padding {
-            return &mut [];
-        }
-
-        // If the storage is large enough, realign it and return
-        storage = &mut storage[realignment_padding..];
-        debug_assert_eq!((storage.as_ptr() as usize) % info_alignment, 0);
-        storage
+    pub fn realign_storage(storage: &mut [u8]) -> &mut [u8] {
+        Self::align_buf(storage).unwrap_or_default()
     }
 
     /// Create a NamedFileProtocolInfo structure in user-provided storage
@@ -119,12 +149,63 @@ impl<Header> NamedFileProtocolInfo<Header> {
         info.name[name_length_ucs2 - 1] = NUL_16;
         Ok(info)
     }
+
+    /// Exact storage size, in bytes, needed to hold this type for a given name
+    ///
+    /// This is the size of the header plus a null-terminated UCS-2 version of
+    /// `name`. A buffer of at least this size that is also correctly aligned
+    /// (see [`Align`]) is guaranteed to satisfy the storage check of `new`, so
+    /// callers doing their own allocation can size a buffer in one call instead
+    /// of triggering the `InsufficientStorage` error path. A name containing
+    /// code points outside UCS-2 still fails with `InvalidChar`.
+    pub fn required_size(name: &str) -> usize {
+        let name_length_ucs2 = name.chars().count() + 1;
+        mem::size_of::<Header>() + name_length_ucs2 * mem::size_of::<Char16>()
+    }
+
+    /// Build this type in a freshly allocated, correctly aligned heap block
+    ///
+    /// Unlike `new_impl`, which writes into caller storage, this sizes and
+    /// allocates an exactly-fitting block through the global allocator, builds
+    /// the DST in place, and returns an owning `Box` carrying the correct
+    /// fat-pointer length.
+    #[cfg(feature = "alloc")]
+    fn new_boxed_impl(
+        header: Header,
+        name: &str,
+    ) -> result::Result<Box<Self>, FileInfoCreationError> {
+        let size = Self::required_size(name);
+        // Match the layout `Box`/`Layout::for_value` will use on drop: the DST
+        // size padded up to the alignment. Allocating and freeing with
+        // differently-sized layouts would violate the GlobalAlloc contract.
+        let layout = alloc::alloc::Layout::from_size_align(size, Self::alignment())
+            .expect("invalid FileProtocolInfo layout")
+            .pad_to_align();
+        unsafe {
+            let ptr = alloc::alloc::alloc(layout);
+            if ptr.is_null() {
+                alloc::alloc::handle_alloc_error(layout);
+            }
+
+            // The block is exactly aligned, so new_impl will not need to shift
+            // the storage and the returned reference points at `ptr`.
+            let storage = slice::from_raw_parts_mut(ptr, size);
+            match Self::new_impl(storage, header, name) {
+                Ok(info) => Ok(Box::from_raw(info as *mut Self)),
+                Err(err) => {
+                    alloc::alloc::dealloc(ptr, layout);
+                    Err(err)
+                }
+            }
+        }
+    }
 }
 
 impl<Header> FromUefi for NamedFileProtocolInfo<Header> {
     #[allow(clippy::cast_ptr_alignment)]
     unsafe fn from_uefi<'a>(raw_ptr: *mut c_void) -> &'a mut Self {
         let byte_ptr = raw_ptr as *mut u8;
+        debug_assert_eq!((raw_ptr as usize) % Self::alignment(), 0);
         let name_ptr = byte_ptr.add(mem::size_of::<Header>()) as *mut Char16;
         let name = CStr16::from_ptr(name_ptr);
         let name_len = name.to_u16_slice_with_nul().len();
@@ -185,6 +266,32 @@ unsafe impl Identify for FileInfo {
     );
 }
 
+/// Construction mode controlling how a FileInfo records its timestamps
+///
+/// Modeled after the `tar` crate's `HeaderMode`. `Deterministic` exploits the
+/// UEFI rule (documented on FileInfo) that a zero time value is ignored during
+/// a set_info(): by zeroing all three time fields it makes the meaningful bytes
+/// of the structure — the header and the null-terminated name — identical
+/// across runs, which is useful for reproducible disk-image tooling and
+/// golden-file tests. Note that any trailing alignment padding past the null
+/// terminator is not written and retains whatever the caller's buffer held, so
+/// golden comparisons should cover `required_size(name)` bytes rather than the
+/// padded `size_of_val`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileInfoMode {
+    /// Record every timestamp exactly as supplied by the caller
+    Complete,
+
+    /// Zero all timestamps, leaving them untouched during a set_info()
+    Deterministic,
+}
+
+impl Default for FileInfoMode {
+    fn default() -> Self {
+        FileInfoMode::Complete
+    }
+}
+
 impl FileInfo {
     /// Create a FileInfo structure
     ///
@@ -207,6 +314,43 @@ impl FileInfo {
         attribute: FileAttribute,
         file_name: &str,
     ) -> result::Result<&'a mut Self, FileInfoCreationError> {
+        Self::new_with_mode(
+            storage,
+            file_size,
+            physical_size,
+            create_time,
+            last_access_time,
+            modification_time,
+            attribute,
+            file_name,
+            FileInfoMode::Complete,
+        )
+    }
+
+    /// Create a FileInfo structure using the given construction mode
+    ///
+    /// This behaves like [`FileInfo::new`], except that a `Deterministic` mode
+    /// overrides the three supplied timestamps with a zero time value, so that
+    /// the produced structure is reproducible across runs.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_mode<'a>(
+        storage: &'a mut [u8],
+        file_size: u64,
+        physical_size: u64,
+        create_time: Time,
+        last_access_time: Time,
+        modification_time: Time,
+        attribute: FileAttribute,
+        file_name: &str,
+        mode: FileInfoMode,
+    ) -> result::Result<&'a mut Self, FileInfoCreationError> {
+        let (create_time, last_access_time, modification_time) = match mode {
+            FileInfoMode::Complete => (create_time, last_access_time, modification_time),
+            // A zeroed Time is the documented "ignore this field" sentinel.
+            FileInfoMode::Deterministic => unsafe {
+                (mem::zeroed(), mem::zeroed(), mem::zeroed())
+            },
+        };
         let header = FileInfoHeader {
             size: 0,
             file_size,
@@ -217,10 +361,49 @@ impl FileInfo {
             attribute,
         };
         let info = Self::new_impl(storage, header, file_name)?;
-        info.header.size = mem::size_of_val(&info) as u64;
+        info.header.size = mem::size_of_val::<Self>(info) as u64;
+        Ok(info)
+    }
+
+    /// Create a FileInfo structure in a freshly allocated heap block
+    ///
+    /// Unlike [`FileInfo::new`], this sizes and allocates an exactly-fitting,
+    /// correctly aligned block through the global allocator, so the caller need
+    /// not pre-guess a storage buffer and retry on `InsufficientStorage`.
+    #[cfg(feature = "alloc")]
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_boxed(
+        file_size: u64,
+        physical_size: u64,
+        create_time: Time,
+        last_access_time: Time,
+        modification_time: Time,
+        attribute: FileAttribute,
+        file_name: &str,
+    ) -> result::Result<Box<Self>, FileInfoCreationError> {
+        let header = FileInfoHeader {
+            size: 0,
+            file_size,
+            physical_size,
+            create_time,
+            last_access_time,
+            modification_time,
+            attribute,
+        };
+        let mut info = Self::new_boxed_impl(header, file_name)?;
+        info.header.size = mem::size_of_val::<Self>(&*info) as u64;
         Ok(info)
     }
 
+    /// Start building a FileInfo structure field by field
+    ///
+    /// This is a more legible alternative to [`FileInfo::new_with_mode`] when
+    /// many fields keep their default value. Terminate the chain with
+    /// [`FileInfoBuilder::create`] to write the structure into storage.
+    pub fn builder(file_name: &str) -> FileInfoBuilder<'_> {
+        FileInfoBuilder::new(file_name)
+    }
+
     /// File size (number of bytes stored in the file)
     pub fn file_size(&self) -> u64 {
         self.header.file_size
@@ -251,14 +434,244 @@ impl FileInfo {
         self.header.attribute
     }
 
+    /// Set the file size that will be requested during a set_info()
+    ///
+    /// This field is ignored for directories and has no effect on the physical
+    /// size, which is always recomputed by the firmware.
+    pub fn set_file_size(&mut self, file_size: u64) {
+        self.header.file_size = file_size;
+    }
+
+    /// Set the creation time that will be requested during a set_info()
+    ///
+    /// A zero time value causes the firmware to leave the field unchanged.
+    pub fn set_create_time(&mut self, time: Time) {
+        self.header.create_time = time;
+    }
+
+    /// Set the last-access time that will be requested during a set_info()
+    ///
+    /// A zero time value causes the firmware to leave the field unchanged.
+    pub fn set_last_access_time(&mut self, time: Time) {
+        self.header.last_access_time = time;
+    }
+
+    /// Set the modification time that will be requested during a set_info()
+    ///
+    /// A zero time value causes the firmware to leave the field unchanged.
+    pub fn set_modification_time(&mut self, time: Time) {
+        self.header.modification_time = time;
+    }
+
+    /// Set the attribute bits that will be requested during a set_info()
+    ///
+    /// The FileAttribute::DIRECTORY bit cannot be changed and must keep matching
+    /// the file's actual type.
+    pub fn set_attribute(&mut self, attribute: FileAttribute) {
+        self.header.attribute = attribute;
+    }
+
     /// Name of the file
     pub fn file_name(&self) -> &CStr16 {
         unsafe { CStr16::from_ptr(&self.name[0]) }
     }
+
+    /// Truth that this entry is the `.` or `..` directory pseudo-entry
+    ///
+    /// A directory read yields, besides the real files and directories, the
+    /// self (`.`) and parent (`..`) references. Following what embedded-sdmmc's
+    /// shell does when listing a directory, an `ls`-style consumer usually wants
+    /// to skip these. UEFI does not surface FAT's volume-label entry here (the
+    /// `0x08` attribute bit is reserved), so the name is the only thing to key
+    /// off.
+    pub fn is_pseudo_entry(&self) -> bool {
+        // '.' is U+002E; the name slice includes its trailing null.
+        matches!(self.file_name().to_u16_slice_with_nul(), [0x2e, 0] | [0x2e, 0x2e, 0])
+    }
 }
 
 impl FileProtocolInfo for FileInfo {}
 
+impl FileAttribute {
+    /// Truth that this attribute set marks a volume-label entry
+    ///
+    /// This tests the `0x08` bit that FAT uses for its volume-label attribute.
+    /// Note that UEFI reserves that bit in `EFI_FILE_INFO` and does not surface
+    /// a volume-label entry through `Directory::read_entry`, so in practice this
+    /// predicate is always `false` on attributes obtained from a directory read;
+    /// it is provided for callers inspecting raw FAT-style attribute bits. To
+    /// skip the pseudo-entries a UEFI directory actually yields, use
+    /// [`FileInfo::is_pseudo_entry`].
+    pub fn is_volume_label(&self) -> bool {
+        self.bits() & 0x08 != 0
+    }
+}
+
+/// Streaming iterator over the [`FileInfo`] entries of a directory
+///
+/// Because each entry is decoded in place into a single caller-supplied buffer,
+/// only one entry is borrowable at a time; this is therefore a streaming
+/// (lending) iterator exposing [`next_entry`](DirEntryIter::next_entry) rather
+/// than an implementation of [`Iterator`]. Obtain one through
+/// [`FileInfo::iter_dir`] or [`FileInfo::iter_dir_filtered`].
+pub struct DirEntryIter<'dir, 'buf> {
+    dir: &'dir mut Directory,
+    buf: &'buf mut [u8],
+    skip_pseudo: bool,
+}
+
+impl DirEntryIter<'_, '_> {
+    /// Read and decode the next directory entry
+    ///
+    /// Returns `Ok(None)` at the end of the directory, `Ok(Some(entry))` for a
+    /// decoded entry, and `Err(..)` if the read fails — in particular a
+    /// `BUFFER_TOO_SMALL` error carries the required buffer size, so an
+    /// oversized entry is reported rather than silently ending the listing.
+    /// When the iterator was created with [`FileInfo::iter_dir_filtered`], the
+    /// `.`/`..` pseudo-entries are transparently skipped.
+    pub fn next_entry(&mut self) -> crate::Result<Option<&FileInfo>, Option<usize>> {
+        // Hand the directory a correctly aligned view of the buffer, so the
+        // decoded FileInfo satisfies the Align contract.
+        let buf = match FileInfo::align_buf(&mut *self.buf) {
+            Some(buf) => buf,
+            None => return Err(Status::BAD_BUFFER_SIZE.into()),
+        };
+        let entry = match self.dir.read_entry(buf)? {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        if self.skip_pseudo && entry.is_pseudo_entry() {
+            return self.next_entry();
+        }
+        Ok(Some(&*entry))
+    }
+}
+
+impl FileInfo {
+    /// Iterate over the entries of `dir`, decoding each into `buf`
+    ///
+    /// The returned streaming iterator reads one entry at a time into the
+    /// caller-supplied buffer, realigns it via the [`Align`] logic, and yields
+    /// a borrowed `&FileInfo`.
+    pub fn iter_dir<'dir, 'buf>(
+        dir: &'dir mut Directory,
+        buf: &'buf mut [u8],
+    ) -> DirEntryIter<'dir, 'buf> {
+        DirEntryIter {
+            dir,
+            buf,
+            skip_pseudo: false,
+        }
+    }
+
+    /// Like [`FileInfo::iter_dir`], but transparently dropping pseudo-entries
+    ///
+    /// The `.`/`..` directory references are skipped, so a typical `ls`-style
+    /// consumer only sees real files and directories.
+    pub fn iter_dir_filtered<'dir, 'buf>(
+        dir: &'dir mut Directory,
+        buf: &'buf mut [u8],
+    ) -> DirEntryIter<'dir, 'buf> {
+        DirEntryIter {
+            dir,
+            buf,
+            skip_pseudo: true,
+        }
+    }
+}
+
+/// Builder for [`FileInfo`] structures
+///
+/// Every field starts at a neutral default (a zero size, a zeroed — hence
+/// ignored — timestamp, and no attribute bits) so that only the fields that
+/// matter to the caller need to be set. Call [`FileInfoBuilder::create`] to
+/// write the finished structure into a storage buffer.
+pub struct FileInfoBuilder<'name> {
+    file_size: u64,
+    physical_size: u64,
+    create_time: Time,
+    last_access_time: Time,
+    modification_time: Time,
+    attribute: FileAttribute,
+    file_name: &'name str,
+    mode: FileInfoMode,
+}
+
+impl<'name> FileInfoBuilder<'name> {
+    /// Start a builder for a file with the given name
+    pub fn new(file_name: &'name str) -> Self {
+        // A zeroed Time is the documented "ignore this field" sentinel.
+        let zero = unsafe { mem::zeroed() };
+        Self {
+            file_size: 0,
+            physical_size: 0,
+            create_time: zero,
+            last_access_time: zero,
+            modification_time: zero,
+            attribute: FileAttribute::empty(),
+            file_name,
+            mode: FileInfoMode::Complete,
+        }
+    }
+
+    /// File size (number of bytes stored in the file)
+    pub fn file_size(mut self, file_size: u64) -> Self {
+        self.file_size = file_size;
+        self
+    }
+
+    /// Physical space consumed by the file on the file system volume
+    pub fn physical_size(mut self, physical_size: u64) -> Self {
+        self.physical_size = physical_size;
+        self
+    }
+
+    /// Time when the file was created
+    pub fn create_time(mut self, create_time: Time) -> Self {
+        self.create_time = create_time;
+        self
+    }
+
+    /// Time when the file was last accessed
+    pub fn last_access_time(mut self, last_access_time: Time) -> Self {
+        self.last_access_time = last_access_time;
+        self
+    }
+
+    /// Time when the file's contents were last modified
+    pub fn modification_time(mut self, modification_time: Time) -> Self {
+        self.modification_time = modification_time;
+        self
+    }
+
+    /// Attribute bits for the file
+    pub fn attribute(mut self, attribute: FileAttribute) -> Self {
+        self.attribute = attribute;
+        self
+    }
+
+    /// Construction mode controlling how timestamps are recorded
+    pub fn mode(mut self, mode: FileInfoMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Write the configured FileInfo structure into the provided storage
+    pub fn create(self, storage: &mut [u8]) -> result::Result<&mut FileInfo, FileInfoCreationError> {
+        FileInfo::new_with_mode(
+            storage,
+            self.file_size,
+            self.physical_size,
+            self.create_time,
+            self.last_access_time,
+            self.modification_time,
+            self.attribute,
+            self.file_name,
+            self.mode,
+        )
+    }
+}
+
 /// System volume information
 ///
 /// May only be obtained on the root directory's file handle.
@@ -313,7 +726,33 @@ impl FileSystemInfo {
             block_size,
         };
         let info = Self::new_impl(storage, header, volume_label)?;
-        info.header.size = mem::size_of_val(&info) as u64;
+        info.header.size = mem::size_of_val::<Self>(info) as u64;
+        Ok(info)
+    }
+
+    /// Create a FileSystemInfo structure in a freshly allocated heap block
+    ///
+    /// Unlike [`FileSystemInfo::new`], this sizes and allocates an
+    /// exactly-fitting, correctly aligned block through the global allocator,
+    /// so the caller need not pre-guess a storage buffer and retry on
+    /// `InsufficientStorage`.
+    #[cfg(feature = "alloc")]
+    pub fn new_boxed(
+        read_only: bool,
+        volume_size: u64,
+        free_space: u64,
+        block_size: u32,
+        volume_label: &str,
+    ) -> result::Result<Box<Self>, FileInfoCreationError> {
+        let header = FileSystemInfoHeader {
+            size: 0,
+            read_only,
+            volume_size,
+            free_space,
+            block_size,
+        };
+        let mut info = Self::new_boxed_impl(header, volume_label)?;
+        info.header.size = mem::size_of_val::<Self>(&*info) as u64;
         Ok(info)
     }
 
@@ -322,6 +761,14 @@ impl FileSystemInfo {
         self.header.read_only
     }
 
+    /// Set the read-only flag that will be requested during a set_info()
+    ///
+    /// As stated on [`FileSystemInfo`], only the volume label may be set using
+    /// this structure, so this does not change the volume's read-only state.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.header.read_only = read_only;
+    }
+
     /// Number of bytes managed by the file system
     pub fn volume_size(&self) -> u64 {
         self.header.volume_size
@@ -381,6 +828,18 @@ impl FileSystemVolumeLabel {
         Self::new_impl(storage, header, volume_label)
     }
 
+    /// Create a FileSystemVolumeLabel structure in a freshly allocated heap block
+    ///
+    /// Unlike [`FileSystemVolumeLabel::new`], this sizes and allocates an
+    /// exactly-fitting, correctly aligned block through the global allocator,
+    /// so the caller need not pre-guess a storage buffer and retry on
+    /// `InsufficientStorage`.
+    #[cfg(feature = "alloc")]
+    pub fn new_boxed(volume_label: &str) -> result::Result<Box<Self>, FileInfoCreationError> {
+        let header = FileSystemVolumeLabelHeader {};
+        Self::new_boxed_impl(header, volume_label)
+    }
+
     /// Volume label
     pub fn volume_label(&self) -> &CStr16 {
         unsafe { CStr16::from_ptr(&self.name[0]) }